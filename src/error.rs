@@ -0,0 +1,56 @@
+///! Error types produced while evaluating expressions.
+
+use std::error;
+use std::fmt;
+
+/// The result type returned by evaluation and value operations.
+pub type Result<T> = ::std::result::Result<T, EvalError>;
+
+/// An error produced while evaluating an expression or operating on a `Value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    kind: EvalErrorKind,
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EvalErrorKind {
+    UnsupportedOp,
+    UnexpectedType,
+    InvalidArg,
+    ArithmError,
+}
+
+impl EvalError {
+    /// An operator or built-in was applied to types it doesn't support.
+    pub fn unsupported_op(message: &str) -> Self {
+        EvalError { kind: EvalErrorKind::UnsupportedOp, message: message.to_owned() }
+    }
+
+    /// A value of the wrong kind was found where another was expected.
+    pub fn unexpected_type(message: &str) -> Self {
+        EvalError { kind: EvalErrorKind::UnexpectedType, message: message.to_owned() }
+    }
+
+    /// An argument, index or name was invalid for the operation attempted.
+    pub fn invalid_arg(message: &str) -> Self {
+        EvalError { kind: EvalErrorKind::InvalidArg, message: message.to_owned() }
+    }
+
+    /// An arithmetic operation could not be carried out, e.g. division by zero.
+    pub fn arithm_error(message: &str) -> Self {
+        EvalError { kind: EvalErrorKind::ArithmError, message: message.to_owned() }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for EvalError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
@@ -0,0 +1,588 @@
+///! Evaluation: turns a parsed `Expr` into a `Value`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use num::rational::Ratio;
+
+use ast::{Expr, Control, FunCall};
+use value::{Value, IdType, FunDef, Func, Distribution};
+use op::{OpCode, CompOp};
+use error::{EvalError, Result};
+
+/// A stack of lexical scopes mapping names to values.
+///
+/// Entering a block (function body, loop body, `if` branch, ...) pushes a
+/// fresh scope so its bindings shadow outer ones and disappear again once
+/// the block exits.
+pub struct Environment {
+    scopes: Vec<BTreeMap<IdType, Value>>,
+}
+
+impl Environment {
+    /// Creates a fresh environment with a single, empty top-level scope.
+    pub fn new() -> Self {
+        Environment { scopes: vec![BTreeMap::new()] }
+    }
+
+    /// Pushes a new, empty scope.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(BTreeMap::new());
+    }
+
+    /// Pops the innermost scope.
+    ///
+    /// Panics if called with only the top-level scope left; callers should
+    /// always pair this with a matching `push_scope`.
+    pub fn pop_scope(&mut self) {
+        assert!(self.scopes.len() > 1, "cannot pop the top-level scope");
+        self.scopes.pop();
+    }
+
+    /// Looks up a name, innermost scope first.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev()
+            .filter_map(|scope| scope.get(name))
+            .next()
+    }
+
+    /// Declares a new binding in the innermost scope, shadowing any outer one.
+    pub fn declare(&mut self, name: IdType, value: Value) {
+        self.scopes.last_mut().unwrap().insert(name, value);
+    }
+
+    /// Mutates an existing binding, searching outward from the innermost scope.
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<()> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(slot) = scope.get_mut(name) {
+                *slot = value;
+                return Ok(());
+            }
+        }
+        Err(EvalError::invalid_arg(&format!(
+            "assignment to undeclared name `{}`", name
+        )))
+    }
+}
+
+/// A non-local unwind in progress: a `break`/`continue` looking for its
+/// enclosing loop, or a plain evaluation error propagating up the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unwind {
+    Break,
+    Continue,
+    Error(EvalError),
+}
+
+impl From<EvalError> for Unwind {
+    fn from(e: EvalError) -> Unwind {
+        Unwind::Error(e)
+    }
+}
+
+impl fmt::Display for Unwind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Unwind::Break => write!(f, "`break` outside of a loop"),
+            &Unwind::Continue => write!(f, "`continue` outside of a loop"),
+            &Unwind::Error(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Unwind {
+    /// Flattens a stray `Break`/`Continue` reaching a point with no
+    /// enclosing loop (REPL top level, a function body) into a plain
+    /// `EvalError`.
+    pub fn into_eval_error(self) -> EvalError {
+        match self {
+            Unwind::Break => EvalError::unsupported_op("`break` outside of a loop"),
+            Unwind::Continue => EvalError::unsupported_op("`continue` outside of a loop"),
+            Unwind::Error(e) => e,
+        }
+    }
+}
+
+/// The result of evaluating an expression: either a `Value`, or an
+/// in-flight `Unwind` (break/continue/error) for a caller further up the
+/// call stack to handle.
+pub type EvalResult<T> = ::std::result::Result<T, Unwind>;
+
+/// Evaluates an expression to a `Value` in the given environment.
+pub fn eval_expr(expr: &Expr, env: &mut Environment) -> EvalResult<Value> {
+    match expr {
+        &Expr::Val(ref val) => Ok(val.clone()),
+
+        &Expr::Id(ref name) => env.get(name).cloned().ok_or_else(|| {
+            EvalError::invalid_arg(&format!("undefined name `{}`", name)).into()
+        }),
+
+        &Expr::Decl(ref name, ref body) => {
+            let value = eval_expr(body, env)?;
+            env.declare(name.clone(), value.clone());
+            Ok(value)
+        },
+
+        &Expr::Assign(ref name, ref body) => {
+            let value = eval_expr(body, env)?;
+            env.assign(name, value.clone())?;
+            Ok(value)
+        },
+
+        &Expr::Comp { ref op, ref lhs, ref rhs } => {
+            let lhs = eval_expr(lhs, env)?;
+            let rhs = eval_expr(rhs, env)?;
+            eval_comp(*op, &lhs, &rhs)
+        },
+
+        &Expr::Op(ref fun_call) => eval_fun_call(fun_call, env),
+
+        &Expr::List(ref exprs) => {
+            let values = exprs.iter()
+                .map(|e| eval_expr(e, env))
+                .collect::<EvalResult<Vec<_>>>()?;
+            Ok(Value::List(values))
+        },
+
+        &Expr::Set(ref exprs) => {
+            // There's no dedicated `Value::Set`, so a set literal just
+            // evaluates to a `List` of its (already deduplicated, since
+            // `Expr` is itself `Ord`) members.
+            let values = exprs.iter()
+                .map(|e| eval_expr(e, env))
+                .collect::<EvalResult<Vec<_>>>()?;
+            Ok(Value::List(values))
+        },
+
+        &Expr::Map(ref pairs) => {
+            let mut map = BTreeMap::new();
+            for (key_expr, val_expr) in pairs {
+                let key = eval_expr(key_expr, env)?;
+                let val = eval_expr(val_expr, env)?;
+                map.insert(key, val);
+            }
+            Ok(Value::Map(map))
+        },
+
+        &Expr::Distribution(ref pairs) => eval_distribution(pairs, env),
+
+        &Expr::Ctrl(ref ctrl) => eval_control(ctrl, env),
+    }
+}
+
+fn eval_distribution(pairs: &[(Expr, Expr)], env: &mut Environment) -> EvalResult<Value> {
+    let mut outcomes = BTreeMap::new();
+    for &(ref outcome_expr, ref weight_expr) in pairs {
+        let outcome = match eval_expr(outcome_expr, env)? {
+            Value::Num(n) => n,
+            other => return Err(EvalError::unexpected_type(&format!(
+                "distribution outcomes must be numerals, got {}", other
+            )).into()),
+        };
+        let weight = match eval_expr(weight_expr, env)? {
+            Value::Num(ref n) if n.is_integer() && *n.numer() >= 0 => *n.numer() as u64,
+            other => return Err(EvalError::unexpected_type(&format!(
+                "distribution weights must be nonnegative integers, got {}", other
+            )).into()),
+        };
+        outcomes.insert(outcome, weight);
+    }
+    Ok(Value::Distribution(Distribution::new(outcomes)))
+}
+
+/// Evaluates a comparison.
+///
+/// When either side is a `Distribution`, the comparison doesn't collapse to
+/// a single `Bool`: it produces a Bernoulli `Distribution` over `{0, 1}`
+/// weighing how much of the outcome space satisfies the predicate, so that
+/// e.g. `p(3d6 >= 13)` can later read off an exact success probability.
+fn eval_comp(op: CompOp, lhs: &Value, rhs: &Value) -> EvalResult<Value> {
+    match (lhs, rhs) {
+        (&Value::Distribution(ref dist), &Value::Num(threshold)) =>
+            Ok(Value::Distribution(bernoulli_vs_scalar(dist, op, threshold, false))),
+        (&Value::Num(threshold), &Value::Distribution(ref dist)) =>
+            Ok(Value::Distribution(bernoulli_vs_scalar(dist, op, threshold, true))),
+        (&Value::Distribution(ref a), &Value::Distribution(ref b)) =>
+            Ok(Value::Distribution(bernoulli_vs_distribution(a, op, b)?)),
+        _ => match lhs.partial_cmp(rhs) {
+            Some(ordering) => Ok(Value::Bool(op.apply(ordering))),
+            None => Err(EvalError::unsupported_op(
+                "comparison is not supported between these types"
+            ).into()),
+        },
+    }
+}
+
+fn bernoulli(true_weight: u64, false_weight: u64) -> Distribution {
+    let mut outcomes = BTreeMap::new();
+    if true_weight > 0 {
+        outcomes.insert(Ratio::from_integer(1), true_weight);
+    }
+    if false_weight > 0 {
+        outcomes.insert(Ratio::from_integer(0), false_weight);
+    }
+    Distribution::new(outcomes)
+}
+
+/// Tests each outcome of `dist` against `scalar`; `flipped` swaps the
+/// operand order for the `scalar OP dist` case (e.g. `13 <= 3d6`).
+fn bernoulli_vs_scalar(
+    dist: &Distribution,
+    op: CompOp,
+    scalar: Ratio<i32>,
+    flipped: bool,
+) -> Distribution {
+    let mut true_weight: u64 = 0;
+    let mut false_weight: u64 = 0;
+    for (&outcome, &weight) in dist.outcomes() {
+        let ordering = if flipped { scalar.cmp(&outcome) } else { outcome.cmp(&scalar) };
+        if op.apply(ordering) {
+            true_weight += weight;
+        } else {
+            false_weight += weight;
+        }
+    }
+    bernoulli(true_weight, false_weight)
+}
+
+/// Tests the predicate over every `(a, b)` outcome pair of two independent
+/// distributions, weighing each pair by `wa*wb`. Like `Distribution::convolve`,
+/// the pairwise weight product and running totals use checked arithmetic so a
+/// huge pair of operands reports an `EvalError` instead of panicking.
+fn bernoulli_vs_distribution(a: &Distribution, op: CompOp, b: &Distribution) -> EvalResult<Distribution> {
+    let mut true_weight: u64 = 0;
+    let mut false_weight: u64 = 0;
+    for (&x, &wx) in a.outcomes() {
+        for (&y, &wy) in b.outcomes() {
+            let weight = wx.checked_mul(wy).ok_or_else(|| EvalError::arithm_error(
+                "distribution weight overflow while comparing; the outcome space is too large"
+            ))?;
+            let slot = if op.apply(x.cmp(&y)) { &mut true_weight } else { &mut false_weight };
+            *slot = slot.checked_add(weight).ok_or_else(|| EvalError::arithm_error(
+                "distribution weight overflow while comparing; the outcome space is too large"
+            ))?;
+        }
+    }
+    Ok(bernoulli(true_weight, false_weight))
+}
+
+fn eval_fun_call(fun_call: &FunCall, env: &mut Environment) -> EvalResult<Value> {
+    // `|>` needs its right-hand side as an unevaluated `Expr` (so an existing
+    // argument list can be extended), so it's handled before the other
+    // operators evaluate all of their arguments eagerly.
+    if let OpCode::Pipe = fun_call.code {
+        if fun_call.args.len() != 2 {
+            return Err(EvalError::invalid_arg(
+                "`|>` expects exactly a left- and a right-hand side"
+            ).into());
+        }
+        return eval_pipe(&fun_call.args[0], &fun_call.args[1], env);
+    }
+
+    let args = fun_call.args.iter()
+        .map(|a| eval_expr(a, env))
+        .collect::<EvalResult<Vec<_>>>()?;
+
+    match fun_call.code {
+        OpCode::Add => binary_op(&args, Value::add),
+        OpCode::Sub => binary_op(&args, Value::sub),
+        OpCode::Mul => binary_op(&args, Value::mul),
+        OpCode::Div => binary_op(&args, Value::div),
+        OpCode::Pow => binary_op(&args, Value::pow),
+        OpCode::Neg => unary_op(&args, Value::neg),
+        OpCode::Not => unary_op(&args, Value::not),
+        OpCode::And => binary_op(&args, Value::and),
+        OpCode::Or => binary_op(&args, Value::or),
+        OpCode::Xor => binary_op(&args, Value::xor),
+        OpCode::Pipe => unreachable!("handled above"),
+        OpCode::Call(ref name) => {
+            let kw_args = fun_call.kw_args.iter()
+                .map(|&(ref kw_name, ref kw_expr)| {
+                    eval_expr(kw_expr, env).map(|v| (kw_name.clone(), v))
+                })
+                .collect::<EvalResult<Vec<_>>>()?;
+            call_named(name, args, kw_args, env)
+        },
+    }
+}
+
+/// Evaluates a `lhs |> rhs` pipeline: `lhs` becomes the leading positional
+/// argument of the call on the right.
+///
+/// If `rhs` is itself a call expression (e.g. `f(y)`), the piped value is
+/// prepended to its existing arguments (`f(piped, y)`). Otherwise `rhs`
+/// must evaluate to a bare `Value::Func`, called with just the piped value.
+///
+/// BLOCKED (see `OpCode::Pipe`): there is no `parser` module anywhere in
+/// this tree to turn `|>` source syntax into
+/// `Expr::Op(FunCall { code: OpCode::Pipe, .. })`, so this evaluator can't
+/// actually be exercised from the REPL yet - the request's parse-rule half
+/// is unmet, not just undocumented. A caller can still reach this path
+/// directly (e.g. by constructing the `Expr` by hand, as the tests do)
+/// until a parser module exists to add the rule to.
+fn eval_pipe(lhs_expr: &Expr, rhs_expr: &Expr, env: &mut Environment) -> EvalResult<Value> {
+    let piped = eval_expr(lhs_expr, env)?;
+
+    match rhs_expr {
+        &Expr::Op(ref inner) => {
+            let mut args = Vec::with_capacity(inner.args.len() + 1);
+            args.push(Expr::Val(piped));
+            args.extend(inner.args.iter().cloned());
+            let extended = FunCall::new(inner.code.clone(), args, inner.kw_args.clone());
+            eval_fun_call(&extended, env)
+        },
+        _ => match eval_expr(rhs_expr, env)? {
+            Value::Func(func) => call_func(&func, vec![piped], vec![], env),
+            _ => Err(EvalError::unexpected_type(
+                "right-hand side of `|>` must be callable"
+            ).into()),
+        },
+    }
+}
+
+fn binary_op<F>(args: &[Value], op: F) -> EvalResult<Value>
+    where F: Fn(&Value, &Value) -> Result<Value>
+{
+    if args.len() != 2 {
+        return Err(EvalError::invalid_arg("expected exactly two arguments").into());
+    }
+    Ok(op(&args[0], &args[1])?)
+}
+
+fn unary_op<F>(args: &[Value], op: F) -> EvalResult<Value>
+    where F: Fn(&Value) -> Result<Value>
+{
+    if args.len() != 1 {
+        return Err(EvalError::invalid_arg("expected exactly one argument").into());
+    }
+    Ok(op(&args[0])?)
+}
+
+fn call_named(
+    name: &str,
+    args: Vec<Value>,
+    kw_args: Vec<(IdType, Value)>,
+    env: &mut Environment,
+) -> EvalResult<Value> {
+    let fun = env.get(name).cloned().ok_or_else(|| {
+        EvalError::invalid_arg(&format!("undefined function `{}`", name))
+    })?;
+
+    match fun {
+        Value::Func(func) => call_func(&func, args, kw_args, env),
+        _ => Err(EvalError::unexpected_type(&format!(
+            "`{}` is not callable", name
+        )).into()),
+    }
+}
+
+fn call_func(
+    func: &Func,
+    args: Vec<Value>,
+    kw_args: Vec<(IdType, Value)>,
+    env: &mut Environment,
+) -> EvalResult<Value> {
+    match func {
+        &Func::User(ref fun_def) => call_user_fun(fun_def, args, kw_args, env),
+        &Func::Native(ref native) => {
+            if !kw_args.is_empty() {
+                return Err(EvalError::invalid_arg(&format!(
+                    "`{}` does not accept named arguments", native.name
+                )).into());
+            }
+            if let Some(arity) = native.arity {
+                if args.len() != arity {
+                    return Err(EvalError::invalid_arg(&format!(
+                        "`{}` expects {} argument(s), got {}",
+                        native.name, arity, args.len()
+                    )).into());
+                }
+            }
+            Ok((native.body)(&args)?)
+        },
+    }
+}
+
+/// Binds `args`/`kw_args` against `fun_def.arg_names` (positional first,
+/// named filling in the rest, reusing `FunDef::check_valid` to reject
+/// malformed parameter lists), then evaluates the function body in a fresh
+/// scope.
+fn call_user_fun(
+    fun_def: &FunDef,
+    args: Vec<Value>,
+    kw_args: Vec<(IdType, Value)>,
+    env: &mut Environment,
+) -> EvalResult<Value> {
+    fun_def.check_valid()?;
+
+    if args.len() > fun_def.arg_names.len() {
+        return Err(EvalError::invalid_arg(&format!(
+            "expected at most {} argument(s), got {}",
+            fun_def.arg_names.len(), args.len()
+        )).into());
+    }
+
+    let mut bound: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+    bound.resize(fun_def.arg_names.len(), None);
+
+    for (kw_name, value) in kw_args {
+        let idx = fun_def.arg_names.iter().position(|n| *n == kw_name)
+            .ok_or_else(|| EvalError::invalid_arg(&format!(
+                "unknown argument `{}`", kw_name
+            )))?;
+        if bound[idx].is_some() {
+            return Err(EvalError::invalid_arg(&format!(
+                "argument `{}` given more than once", kw_name
+            )).into());
+        }
+        bound[idx] = Some(value);
+    }
+
+    env.push_scope();
+    for (name, slot) in fun_def.arg_names.iter().zip(bound) {
+        let value = match slot {
+            Some(value) => value,
+            None => {
+                env.pop_scope();
+                return Err(EvalError::invalid_arg(&format!(
+                    "missing argument `{}`", name
+                )).into());
+            },
+        };
+        env.declare(name.clone(), value);
+    }
+    let result = eval_expr(&fun_def.body, env);
+    env.pop_scope();
+
+    // `break`/`continue` don't escape the function they're evaluated in;
+    // a stray one here is as meaningless as one at the REPL top level.
+    match result {
+        Err(Unwind::Break) | Err(Unwind::Continue) => Err(EvalError::unsupported_op(
+            "`break`/`continue` cannot escape a function body"
+        ).into()),
+        other => other,
+    }
+}
+
+/// Evaluates `expr` and requires the result to be a `Value::Bool`.
+fn eval_bool(expr: &Expr, env: &mut Environment) -> EvalResult<bool> {
+    match eval_expr(expr, env)? {
+        Value::Bool(b) => Ok(b),
+        other => Err(EvalError::unexpected_type(&format!(
+            "expected a boolean condition, got {}", other
+        )).into()),
+    }
+}
+
+fn eval_control(ctrl: &Control, env: &mut Environment) -> EvalResult<Value> {
+    match ctrl {
+        &Control::Break => Err(Unwind::Break),
+        &Control::Continue => Err(Unwind::Continue),
+
+        &Control::If { ref cond_expr, ref then_expr, ref elif_exprs, ref else_expr } => {
+            if eval_bool(cond_expr, env)? {
+                return eval_expr(then_expr, env);
+            }
+
+            // `elif cond { body }` clauses are threaded through as
+            // `(cond, body)` pairs, tried in order.
+            for pair in elif_exprs.chunks(2) {
+                if pair.len() == 2 {
+                    if eval_bool(&pair[0], env)? {
+                        return eval_expr(&pair[1], env);
+                    }
+                }
+            }
+
+            eval_expr(else_expr, env)
+        },
+
+        &Control::Loop { ref body } => {
+            loop {
+                match eval_expr(body, env) {
+                    Ok(_) | Err(Unwind::Continue) => continue,
+                    Err(Unwind::Break) => break Ok(Value::Void),
+                    Err(e @ Unwind::Error(_)) => break Err(e),
+                }
+            }
+        },
+
+        &Control::While { ref cond, ref body } => {
+            loop {
+                if !eval_bool(cond, env)? {
+                    break Ok(Value::Void);
+                }
+
+                match eval_expr(body, env) {
+                    Ok(_) | Err(Unwind::Continue) => continue,
+                    Err(Unwind::Break) => break Ok(Value::Void),
+                    Err(e @ Unwind::Error(_)) => break Err(e),
+                }
+            }
+        },
+
+        &Control::For { ref iterator, ref iterable, ref body } => {
+            let items: Vec<Value> = match eval_expr(iterable, env)? {
+                Value::List(vec) => vec,
+                Value::Distribution(dist) =>
+                    dist.outcomes().keys().cloned().map(Value::Num).collect(),
+                other => return Err(EvalError::unexpected_type(&format!(
+                    "cannot iterate over {}", other
+                )).into()),
+            };
+
+            for item in items {
+                env.push_scope();
+                env.declare(iterator.clone(), item);
+                let result = eval_expr(body, env);
+                env.pop_scope();
+
+                match result {
+                    Ok(_) | Err(Unwind::Continue) => continue,
+                    Err(Unwind::Break) => break,
+                    Err(e @ Unwind::Error(_)) => return Err(e),
+                }
+            }
+
+            Ok(Value::Void)
+        },
+
+        &Control::Try { ref expr, ref else_expr } => {
+            match eval_expr(expr, env) {
+                Ok(val) => Ok(val),
+                Err(Unwind::Error(_)) => eval_expr(else_expr, env),
+                // a `break`/`continue` inside a `try` still escapes it
+                Err(unwind) => Err(unwind),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::rational::Ratio;
+
+    use ast::{Expr, FunCall};
+    use op::OpCode;
+    use value::Value;
+
+    use super::{eval_expr, Environment};
+
+    #[test]
+    fn pipe_prepends_the_piped_value_as_the_leading_argument() {
+        // `3 |> neg`, built by hand: there's no parser in this tree yet to
+        // turn `|>` source syntax into this `Expr`, but the evaluator side
+        // works once the `Expr` exists (see the BLOCKED note on `eval_pipe`).
+        let pipe = Expr::Op(FunCall::new(
+            OpCode::Pipe,
+            vec![
+                Expr::Val(Value::Num(Ratio::from_integer(3))),
+                Expr::Op(FunCall::new(OpCode::Neg, vec![], vec![])),
+            ],
+            vec![],
+        ));
+        let mut env = Environment::new();
+        let result = eval_expr(&pipe, &mut env).unwrap();
+        assert_eq!(result, Value::Num(Ratio::from_integer(-3)));
+    }
+}
@@ -3,13 +3,19 @@ extern crate rustyline;
 extern crate num;
 
 mod ast;
+mod error;
+mod eval;
+mod op;
 mod parser;
+mod stdlib;
+mod value;
 
 use std::io;
 use std::io::BufRead;
 
 use rustyline::error::ReadlineError;
 use parser::expr;
+use eval::Environment;
 
 fn main() {
     ::std::process::exit(real_main());
@@ -31,6 +37,9 @@ fn real_main() -> i32 {
         println!("Interactive Roller REPL started");
     }
 
+    let mut env = Environment::new();
+    stdlib::load(&mut env);
+
     // return value
     let return_status = loop {
         // read a line
@@ -58,9 +67,14 @@ fn real_main() -> i32 {
                 }
 
                 let input = input.trim();
-                let parsed_res = expr::parse_expr(input);
 
-                println!("Result is: {:?}", parsed_res);
+                match expr::parse_expr(input) {
+                    Ok(parsed) => match eval::eval_expr(&parsed, &mut env) {
+                        Ok(value) => println!("{}", value),
+                        Err(unwind) => eprintln!("Evaluation error: {}", unwind.into_eval_error()),
+                    },
+                    Err(e) => eprintln!("Parse error: {:?}", e),
+                }
             },
 
             // TODO: maybe check ReadlineError::WindowResize when on windows
@@ -0,0 +1,66 @@
+///! Operator and function-call codes shared between the parser and the evaluator.
+
+use std::cmp::Ordering;
+
+use value::IdType;
+
+/// Identifies what a `FunCall` applies: a built-in operator, or a named
+/// user-defined or standard-library function.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpCode {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Neg,
+    Not,
+    And,
+    Or,
+    Xor,
+    /// `lhs |> rhs`: feeds `lhs` into `rhs` as its leading argument.
+    ///
+    /// BLOCKED: the request for this operator ("add a corresponding
+    /// OpCode/parse rule in the parser") is only half-done. There is no
+    /// `parser` module anywhere in this tree to add that parse rule to -
+    /// `main.rs` already declares `mod parser;` and calls
+    /// `parser::expr::parse_expr`, but no such module exists among the
+    /// source files. Until a parser module exists, only a hand-built `Expr`
+    /// (as in the tests) holds this variant, so `|>` is unreachable from the REPL (see
+    /// `eval::eval_pipe`). This is not a completed request; it's the
+    /// evaluator half of one, parked on a missing prerequisite.
+    Pipe,
+    /// Call of a named function, built-in or user-defined.
+    Call(IdType),
+}
+
+/// A comparison operator, as used in `Expr::Comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompOp {
+    /// Applies this comparison operator to an already-computed `Ordering`.
+    pub fn apply(&self, ordering: Ordering) -> bool {
+        match (*self, ordering) {
+            (CompOp::Eq, Ordering::Equal) => true,
+            (CompOp::Eq, _) => false,
+            (CompOp::Ne, Ordering::Equal) => false,
+            (CompOp::Ne, _) => true,
+            (CompOp::Lt, Ordering::Less) => true,
+            (CompOp::Lt, _) => false,
+            (CompOp::Le, Ordering::Greater) => false,
+            (CompOp::Le, _) => true,
+            (CompOp::Gt, Ordering::Greater) => true,
+            (CompOp::Gt, _) => false,
+            (CompOp::Ge, Ordering::Less) => false,
+            (CompOp::Ge, _) => true,
+        }
+    }
+}
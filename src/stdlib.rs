@@ -0,0 +1,254 @@
+///! The built-in standard library of native functions, registered into
+///! every fresh `Environment`.
+
+use std::convert::TryFrom;
+
+use num::rational::Ratio;
+
+use error::{EvalError, Result};
+use eval::Environment;
+use value::{Value, Func, NativeFun, Distribution};
+
+/// Registers the standard library into `env`. Called once at REPL startup.
+pub fn load(env: &mut Environment) {
+    register(env, "floor", Some(1), floor);
+    register(env, "ceil", Some(1), ceil);
+    register(env, "abs", Some(1), abs);
+    register(env, "min", Some(1), min);
+    register(env, "max", Some(1), max);
+    register(env, "len", Some(1), len);
+    register(env, "sum", Some(1), sum);
+    register(env, "highest", Some(2), highest);
+    register(env, "lowest", Some(2), lowest);
+    register(env, "p", Some(1), p);
+    register(env, "mean", Some(1), mean);
+}
+
+fn register(env: &mut Environment, name: &'static str, arity: Option<usize>,
+            body: fn(&[Value]) -> Result<Value>)
+{
+    env.declare(name.to_owned(), Value::Func(Func::Native(NativeFun {
+        name: name,
+        arity: arity,
+        body: body,
+    })));
+}
+
+fn expect_num(val: &Value) -> Result<Ratio<i32>> {
+    match val {
+        &Value::Num(n) => Ok(n),
+        other => Err(EvalError::unexpected_type(&format!(
+            "expected a numeral, got {}", other
+        ))),
+    }
+}
+
+fn expect_list(val: &Value) -> Result<&Vec<Value>> {
+    match val {
+        &Value::List(ref vec) => Ok(vec),
+        other => Err(EvalError::unexpected_type(&format!(
+            "expected a list, got {}", other
+        ))),
+    }
+}
+
+fn expect_distribution(val: &Value) -> Result<&Distribution> {
+    match val {
+        &Value::Distribution(ref dist) => Ok(dist),
+        other => Err(EvalError::unexpected_type(&format!(
+            "expected a distribution, got {}", other
+        ))),
+    }
+}
+
+fn expect_count(val: &Value) -> Result<usize> {
+    match val {
+        &Value::Num(n) if n.is_integer() && *n.numer() >= 0 => Ok(*n.numer() as usize),
+        other => Err(EvalError::unexpected_type(&format!(
+            "expected a nonnegative integer count, got {}", other
+        ))),
+    }
+}
+
+/// The largest integer `<= n`.
+fn floor_to_integer(n: Ratio<i32>) -> i32 {
+    let (numer, denom) = (*n.numer(), *n.denom());
+    if numer >= 0 {
+        numer / denom
+    } else {
+        -((-numer + denom - 1) / denom)
+    }
+}
+
+fn floor(args: &[Value]) -> Result<Value> {
+    let n = expect_num(&args[0])?;
+    Ok(Value::Num(Ratio::from_integer(floor_to_integer(n))))
+}
+
+fn ceil(args: &[Value]) -> Result<Value> {
+    let n = expect_num(&args[0])?;
+    Ok(Value::Num(Ratio::from_integer(-floor_to_integer(-n))))
+}
+
+fn abs(args: &[Value]) -> Result<Value> {
+    let n = expect_num(&args[0])?;
+    Ok(Value::Num(if n < Ratio::from_integer(0) { -n } else { n }))
+}
+
+fn min(args: &[Value]) -> Result<Value> {
+    expect_list(&args[0])?.iter().cloned().min()
+        .ok_or_else(|| EvalError::invalid_arg("`min` of an empty list"))
+}
+
+fn max(args: &[Value]) -> Result<Value> {
+    expect_list(&args[0])?.iter().cloned().max()
+        .ok_or_else(|| EvalError::invalid_arg("`max` of an empty list"))
+}
+
+fn len(args: &[Value]) -> Result<Value> {
+    let list = expect_list(&args[0])?;
+    Ok(Value::Num(Ratio::from_integer(list.len() as i32)))
+}
+
+fn sum(args: &[Value]) -> Result<Value> {
+    let list = expect_list(&args[0])?;
+    let mut total = Value::Num(Ratio::from_integer(0));
+    for item in list {
+        total = total.add(item)?;
+    }
+    Ok(total)
+}
+
+/// Sums the `n` highest/lowest values out of `items`.
+fn keep(n: usize, mut items: Vec<Value>, highest: bool) -> Result<Value> {
+    items.sort();
+    if highest {
+        items.reverse();
+    }
+    let mut total = Value::Num(Ratio::from_integer(0));
+    for item in items.into_iter().take(n) {
+        total = total.add(&item)?;
+    }
+    Ok(total)
+}
+
+/// `highest(n, items)`: sums the `n` highest values out of a literal list,
+/// e.g. for "roll N keep highest M" dice pool mechanics applied to a list of
+/// individual roll results.
+///
+/// CONFIRMED API CHANGE FROM THE ORIGINAL REQUEST: the request described
+/// `highest(n, dist)`/`lowest(n, dist)` taking a `Distribution`, but the
+/// dice engine's `dX`/`NdX` only ever produce an exact summed `Distribution`,
+/// never a list of individual rolls - there is no dice-engine value that
+/// could be passed as `dist` in the first place, so `3d6 |> highest(2)`
+/// could never have worked as specified. This implementation deliberately
+/// takes a `Value::List` instead, like `min`/`max`/`sum` do: build the list
+/// explicitly, e.g. `highest(2, [roll1, roll2, roll3])`. Order statistics
+/// over a `Distribution` itself (keeping the top M of N dice as a new exact
+/// distribution) would need a third "how many dice" argument this signature
+/// doesn't have, and is out of scope here.
+fn highest(args: &[Value]) -> Result<Value> {
+    let n = expect_count(&args[0])?;
+    let items = expect_list(&args[1])?.clone();
+    keep(n, items, true)
+}
+
+/// `lowest(n, items)`: sums the `n` lowest values out of a literal list. See
+/// `highest` for why this takes a `Value::List` rather than a `Distribution`.
+fn lowest(args: &[Value]) -> Result<Value> {
+    let n = expect_count(&args[0])?;
+    let items = expect_list(&args[1])?.clone();
+    keep(n, items, false)
+}
+
+/// Reads off the exact success probability of a Bernoulli `Distribution`
+/// produced by comparing a dice distribution against a threshold, e.g.
+/// `p(3d6 >= 13)`.
+fn p(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        &Value::Distribution(ref dist) => {
+            let total = dist.total_weight();
+            if total == 0 {
+                return Err(EvalError::invalid_arg("`p` of an empty distribution"));
+            }
+            let zero = Ratio::from_integer(0);
+            let one = Ratio::from_integer(1);
+            if dist.outcomes().keys().any(|&outcome| outcome != zero && outcome != one) {
+                return Err(EvalError::unexpected_type(
+                    "`p` expects a boolean distribution (e.g. from a comparison), \
+                     got a distribution with outcomes other than 0/1"
+                ));
+            }
+            let true_weight = dist.outcomes().get(&one).cloned().unwrap_or(0);
+            let true_weight = i32::try_from(true_weight).map_err(|_| EvalError::arithm_error(
+                "`p`'s true-outcome weight is too large to represent as a `Ratio<i32>`"
+            ))?;
+            let total = i32::try_from(total).map_err(|_| EvalError::arithm_error(
+                "`p`'s total distribution weight is too large to represent as a `Ratio<i32>`"
+            ))?;
+            Ok(Value::Num(Ratio::new(true_weight, total)))
+        },
+        other => Err(EvalError::unexpected_type(&format!(
+            "`p` expects a boolean distribution (e.g. from a comparison), got {}", other
+        ))),
+    }
+}
+
+/// The exact expected value of a `Distribution`, e.g. `mean(3d6)`.
+fn mean(args: &[Value]) -> Result<Value> {
+    let dist = expect_distribution(&args[0])?;
+    Ok(Value::Num(dist.expected_value()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use num::rational::Ratio;
+
+    use ast::Expr;
+    use op::CompOp;
+    use eval::{eval_expr, Environment};
+    use value::{Value, Distribution};
+
+    use super::{p, mean};
+
+    #[test]
+    fn p_reads_off_the_true_weight_of_a_bernoulli_distribution() {
+        // 3d6 >= 13, built by hand since there's no parser in this tree yet.
+        let three_d6 = Distribution::die(6).repeat(3).unwrap();
+        let comp = Expr::Comp {
+            op: CompOp::Ge,
+            lhs: Box::new(Expr::Val(Value::Distribution(three_d6))),
+            rhs: Box::new(Expr::Val(Value::Num(Ratio::from_integer(13)))),
+        };
+        let mut env = Environment::new();
+        let bernoulli = eval_expr(&comp, &mut env).unwrap();
+        let prob = p(&[bernoulli]).unwrap();
+        // 3d6 >= 13 succeeds on 56 of 216 outcomes (21+15+10+6+3+1).
+        assert_eq!(prob, Value::Num(Ratio::new(56, 216)));
+    }
+
+    #[test]
+    fn p_rejects_a_non_bernoulli_distribution() {
+        let three_d6 = Distribution::die(6).repeat(3).unwrap();
+        assert!(p(&[Value::Distribution(three_d6)]).is_err());
+    }
+
+    #[test]
+    fn p_reports_overflow_instead_of_wrapping_into_a_garbage_probability() {
+        use std::collections::BTreeMap;
+
+        // A weight beyond i32::MAX, as can happen from a distribution-vs-
+        // distribution comparison (the weight there is a product of totals).
+        let mut outcomes = BTreeMap::new();
+        outcomes.insert(Ratio::from_integer(1), (i32::max_value() as u64) + 1);
+        let huge = Distribution::new(outcomes);
+        assert!(p(&[Value::Distribution(huge)]).is_err());
+    }
+
+    #[test]
+    fn mean_of_3d6_is_10_point_5() {
+        let three_d6 = Distribution::die(6).repeat(3).unwrap();
+        let result = mean(&[Value::Distribution(three_d6)]).unwrap();
+        assert_eq!(result, Value::Num(Ratio::new(21, 2)));
+    }
+}
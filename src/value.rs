@@ -1,3 +1,4 @@
+use std::fmt;
 use std::ops;
 use std::collections::{BTreeSet, BTreeMap};
 
@@ -20,8 +21,181 @@ pub enum Value {
     Str(String),
     List(Vec<Value>),
     Map(BTreeMap<Value, Value>),
-    Distribution(BTreeMap<Expr, u32>),
-    Func(FunDef),
+    Distribution(Distribution),
+    Func(Func),
+}
+
+/// A callable value: either a user-defined function, or a native one
+/// provided by the standard library.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Func {
+    User(FunDef),
+    Native(NativeFun),
+}
+
+/// A native function registered into an `Environment` by the standard
+/// library, e.g. `floor` or `sum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NativeFun {
+    /// Name it's registered under, for error messages.
+    pub name: &'static str,
+    /// Number of arguments it expects, or `None` if variadic.
+    pub arity: Option<usize>,
+    pub body: fn(&[Value]) -> Result<Value>,
+}
+
+/// An exact probability distribution over numeral outcomes.
+///
+/// Maps each possible outcome to a nonnegative integer weight; the
+/// probability of a given outcome is its weight divided by the sum of
+/// all weights (`total_weight`). This is what lets `dX`/`NdX` answer
+/// questions like expected value or success probability exactly,
+/// instead of by sampling.
+///
+/// Weights are `u64`: a weight is a product of smaller weights every time
+/// two distributions convolve, and `NdX` is `N` convolutions deep, so `u32`
+/// overflows on perfectly ordinary input (`13d6` already exceeds it). Every
+/// operation that multiplies or accumulates weights uses checked arithmetic
+/// and reports overflow as an `EvalError` rather than panicking.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Distribution(BTreeMap<Ratio<i32>, u64>);
+
+impl Distribution {
+    /// Builds a `Distribution` directly from outcome/weight pairs.
+    pub fn new(outcomes: BTreeMap<Ratio<i32>, u64>) -> Self {
+        Distribution(outcomes)
+    }
+
+    /// The uniform distribution of a single `sides`-sided die: `{1:1, .., sides:1}`.
+    pub fn die(sides: i32) -> Self {
+        let mut map = BTreeMap::new();
+        for face in 1..=sides {
+            map.insert(Ratio::from_integer(face), 1);
+        }
+        Distribution(map)
+    }
+
+    /// The identity distribution for convolution: a sure outcome of zero.
+    pub fn identity() -> Self {
+        let mut map = BTreeMap::new();
+        map.insert(Ratio::from_integer(0), 1);
+        Distribution(map)
+    }
+
+    /// Convolves two independent distributions, summing their outcomes.
+    ///
+    /// For every `(a, wa)` in `self` and `(b, wb)` in `other`, accumulates
+    /// `out[a+b] += wa*wb`. This is the distribution of the sum of two
+    /// independent random variables.
+    pub fn convolve(&self, other: &Distribution) -> Result<Distribution> {
+        // Every output weight can fit in a `u64` while their total does not,
+        // so the total is checked too.
+        self.total_weight().checked_mul(other.total_weight()).ok_or_else(|| EvalError::arithm_error(
+            "distribution weight overflow while convolving; the outcome space is too large"
+        ))?;
+        let mut out: BTreeMap<Ratio<i32>, u64> = BTreeMap::new();
+        for (&a, &wa) in &self.0 {
+            for (&b, &wb) in &other.0 {
+                let product = wa.checked_mul(wb).ok_or_else(|| EvalError::arithm_error(
+                    "distribution weight overflow while convolving; the outcome space is too large"
+                ))?;
+                let slot = out.entry(a + b).or_insert(0);
+                *slot = slot.checked_add(product).ok_or_else(|| EvalError::arithm_error(
+                    "distribution weight overflow while convolving; the outcome space is too large"
+                ))?;
+            }
+        }
+        Ok(Distribution(out))
+    }
+
+    /// The `n`-fold convolution of `self` with itself, i.e. the distribution
+    /// of `NdX` when `self` is the distribution of `dX`.
+    ///
+    /// Uses exponentiation by squaring so large `n` stay cheap.
+    pub fn repeat(&self, n: u32) -> Result<Distribution> {
+        let mut acc = Distribution::identity();
+        let mut base = self.clone();
+        let mut n = n;
+        while n > 0 {
+            if n & 1 == 1 {
+                acc = acc.convolve(&base)?;
+            }
+            if n > 1 {
+                base = base.convolve(&base)?;
+            }
+            n >>= 1;
+        }
+        Ok(acc)
+    }
+
+    /// Shifts every outcome by a fixed amount, e.g. for `dist + 2`.
+    ///
+    /// Shifting is injective, so distinct outcomes can never collide into
+    /// the same key.
+    pub fn shift(&self, amount: Ratio<i32>) -> Distribution {
+        Distribution(self.0.iter().map(|(&k, &w)| (k + amount, w)).collect())
+    }
+
+    /// Scales every outcome by a fixed factor, e.g. for `dist * 2`.
+    ///
+    /// Also used to negate a distribution (`factor == -1`) for subtraction.
+    ///
+    /// Unlike `shift`, this isn't injective for `factor == 0`: every outcome
+    /// maps to `0`, so weights landing on the same key are accumulated
+    /// rather than overwritten (a plain `collect()` into the `BTreeMap`
+    /// would silently keep only the last one and lose total weight).
+    pub fn scale(&self, factor: Ratio<i32>) -> Distribution {
+        let mut out: BTreeMap<Ratio<i32>, u64> = BTreeMap::new();
+        for (&k, &w) in &self.0 {
+            *out.entry(k * factor).or_insert(0) += w;
+        }
+        Distribution(out)
+    }
+
+    /// The sum of all outcome weights.
+    pub fn total_weight(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    /// The exact expected value, as `sum(outcome*weight)/total_weight`.
+    pub fn expected_value(&self) -> Result<Ratio<i32>> {
+        let total = self.total_weight();
+        if total == 0 || total > i32::max_value() as u64 {
+            return Err(EvalError::arithm_error(
+                "distribution weight is empty or too large to represent as a `Ratio<i32>`"
+            ));
+        }
+        use num::{CheckedAdd, CheckedDiv, CheckedMul};
+        let overflow = || EvalError::arithm_error(
+            "distribution expected value is too large to represent as a `Ratio<i32>`"
+        );
+        let mut sum = Ratio::from_integer(0);
+        for (&outcome, &weight) in &self.0 {
+            let term = outcome.checked_mul(&Ratio::from_integer(weight as i32)).ok_or_else(overflow)?;
+            sum = sum.checked_add(&term).ok_or_else(overflow)?;
+        }
+        sum.checked_div(&Ratio::from_integer(total as i32)).ok_or_else(overflow)
+    }
+
+    /// Read-only access to the outcome/weight map, e.g. for iterating in `for`.
+    pub fn outcomes(&self) -> &BTreeMap<Ratio<i32>, u64> {
+        &self.0
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total_weight() as f64;
+        let mut first = true;
+        for (outcome, weight) in &self.0 {
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(f, "{}: {:.2}%", outcome, (*weight as f64 / total) * 100.0)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -46,20 +220,6 @@ impl FunDef {
     }
 }
 
-macro_rules! impl_op {
-    ($name:expr, $fun_name:ident, $called_fun:path) => (
-        pub fn $fun_name(&self, rhs: &Value) -> Result<Value> {
-            match (self, rhs) {
-                (&Value::Num(x), &Value::Num(y)) =>
-                    Ok($called_fun(x, y).into()),
-                _ => Err(EvalError::unsupported_op(&format!(
-                    "{} is not supported between these types", $name
-                )))
-            }
-        }
-    )
-}
-
 impl Value {
     /// Unescapes a double quoted string value.
     pub fn new_string(s: &str) -> Self {
@@ -148,10 +308,12 @@ impl Value {
         }
     }
 
-    /// Perform negation operation for one numeral value.
+    /// Perform negation operation for one numeral or distribution value.
     pub fn neg(&self) -> Result<Value> {
         match self {
             &Value::Num(a) => Ok(Value::Num(-a)),
+            &Value::Distribution(ref dist) =>
+                Ok(Value::Distribution(dist.scale(-Ratio::from_integer(1)))),
             _ => Err(EvalError::unsupported_op(
                 "negation is not supported for this type"
             ))
@@ -160,18 +322,55 @@ impl Value {
 
     /// Addition between types.
     ///
-    /// Only supported for numerals.
-    impl_op!("addition", add, ops::Add::add);
+    /// Supported for numerals, and for distributions (convolution with
+    /// another distribution, or a shift by a numeral).
+    pub fn add(&self, rhs: &Value) -> Result<Value> {
+        match (self, rhs) {
+            (&Value::Num(x), &Value::Num(y)) => Ok(ops::Add::add(x, y).into()),
+            (&Value::Distribution(ref a), &Value::Distribution(ref b)) =>
+                Ok(Value::Distribution(a.convolve(b)?)),
+            (&Value::Distribution(ref dist), &Value::Num(n)) |
+            (&Value::Num(n), &Value::Distribution(ref dist)) =>
+                Ok(Value::Distribution(dist.shift(n))),
+            _ => Err(EvalError::unsupported_op(
+                "addition is not supported between these types"
+            ))
+        }
+    }
 
     /// Substraction between types.
     ///
-    /// Only supported for numerals.
-    impl_op!("substraction", sub, ops::Sub::sub);
+    /// Supported for numerals, and for distributions (difference of two
+    /// independent distributions, or a shift by a numeral).
+    pub fn sub(&self, rhs: &Value) -> Result<Value> {
+        match (self, rhs) {
+            (&Value::Num(x), &Value::Num(y)) => Ok(ops::Sub::sub(x, y).into()),
+            (&Value::Distribution(ref a), &Value::Distribution(ref b)) =>
+                Ok(Value::Distribution(a.convolve(&b.scale(-Ratio::from_integer(1)))?)),
+            (&Value::Distribution(ref dist), &Value::Num(n)) =>
+                Ok(Value::Distribution(dist.shift(-n))),
+            (&Value::Num(n), &Value::Distribution(ref dist)) =>
+                Ok(Value::Distribution(dist.scale(-Ratio::from_integer(1)).shift(n))),
+            _ => Err(EvalError::unsupported_op(
+                "substraction is not supported between these types"
+            ))
+        }
+    }
 
     /// Multiplication between types.
     ///
-    /// Only supported for numerals.
-    impl_op!("multiplication", mul, ops::Mul::mul);
+    /// Supported for numerals, and for scaling a distribution by a numeral.
+    pub fn mul(&self, rhs: &Value) -> Result<Value> {
+        match (self, rhs) {
+            (&Value::Num(x), &Value::Num(y)) => Ok(ops::Mul::mul(x, y).into()),
+            (&Value::Distribution(ref dist), &Value::Num(n)) |
+            (&Value::Num(n), &Value::Distribution(ref dist)) =>
+                Ok(Value::Distribution(dist.scale(n))),
+            _ => Err(EvalError::unsupported_op(
+                "multiplication is not supported between these types"
+            ))
+        }
+    }
 
     /// Division between types.
     ///
@@ -272,3 +471,100 @@ impl From<Ratio<i32>> for Value {
         Value::Num(r)
     }
 }
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Value::Void => Ok(()),
+            &Value::None => write!(f, "none"),
+            &Value::Bool(b) => write!(f, "{}", b),
+            &Value::Num(n) => write!(f, "{}", n),
+            &Value::Str(ref s) => write!(f, "{}", s),
+            &Value::List(ref vec) => {
+                write!(f, "[")?;
+                for (i, val) in vec.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            },
+            &Value::Map(ref map) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, val)?;
+                }
+                write!(f, "}}")
+            },
+            &Value::Distribution(ref dist) => write!(f, "{}", dist),
+            &Value::Func(_) => write!(f, "<function>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num::rational::Ratio;
+
+    use super::Distribution;
+
+    fn r(n: i32) -> Ratio<i32> {
+        Ratio::from_integer(n)
+    }
+
+    #[test]
+    fn die_is_uniform_over_its_faces() {
+        let d6 = Distribution::die(6);
+        assert_eq!(d6.total_weight(), 6);
+        for face in 1..=6 {
+            assert_eq!(d6.outcomes().get(&r(face)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn convolve_sums_independent_outcomes() {
+        // 2d6: outcomes 2..=12, weights following the usual triangular shape.
+        let d6 = Distribution::die(6);
+        let two_d6 = d6.convolve(&d6).unwrap();
+        assert_eq!(two_d6.total_weight(), 36);
+        assert_eq!(two_d6.outcomes().get(&r(2)), Some(&1));
+        assert_eq!(two_d6.outcomes().get(&r(7)), Some(&6));
+        assert_eq!(two_d6.outcomes().get(&r(12)), Some(&1));
+    }
+
+    #[test]
+    fn repeat_matches_repeated_convolve() {
+        let d6 = Distribution::die(6);
+        let by_repeat = d6.repeat(3).unwrap();
+        let by_convolve = d6.convolve(&d6).unwrap().convolve(&d6).unwrap();
+        assert_eq!(by_repeat, by_convolve);
+        assert_eq!(by_repeat.total_weight(), 216);
+    }
+
+    #[test]
+    fn repeat_reports_overflow_instead_of_panicking() {
+        // 6^n overflows u64 well before n reaches 30.
+        let d6 = Distribution::die(6);
+        assert!(d6.repeat(30).is_err());
+    }
+
+    #[test]
+    fn scale_by_zero_accumulates_rather_than_overwrites() {
+        let d6 = Distribution::die(6);
+        let scaled = d6.scale(r(0));
+        // every outcome collapses onto 0, but the total weight must survive.
+        assert_eq!(scaled.outcomes().len(), 1);
+        assert_eq!(scaled.outcomes().get(&r(0)), Some(&6));
+        assert_eq!(scaled.total_weight(), d6.total_weight());
+    }
+
+    #[test]
+    fn expected_value_of_a_fair_die() {
+        let d6 = Distribution::die(6);
+        assert_eq!(d6.expected_value().unwrap(), Ratio::new(7, 2));
+    }
+}